@@ -8,22 +8,25 @@ use std::{
     env, ffi, fs, io,
     io::Write,
     mem,
-    os::unix::{io::AsRawFd, net::UnixStream},
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
     path::{Path, PathBuf},
     slice,
+    sync::{Arc, Mutex},
     time::Instant,
 };
 
 use nix::{
     poll::{poll, PollFd, PollFlags},
     sched::{setns, CloneFlags},
+    sys::signal::{SigSet, Signal},
+    sys::signalfd::{SfdFlags, SignalFd},
     sys::wait::{waitpid, WaitStatus},
-    unistd::{fork, getpgid, ForkResult},
+    unistd::{fork, getpgid, ForkResult, Pid},
 };
 use serde::Serialize;
+use tokio::{io::unix::AsyncFd, sync::mpsc, sync::Mutex as AsyncMutex};
 
 use lazy_static::lazy_static;
-use signal_hook::{consts::SIGTERM, low_level::pipe};
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -50,6 +53,14 @@ impl PartialEq for EventInfo {
     }
 }
 
+// Sent as the final frame on the socket transport so the collector can tell
+// "the monitored workload finished cleanly" apart from an EOF it has to
+// guess about.
+#[derive(Serialize, Debug)]
+struct StreamComplete {
+    complete: bool,
+}
+
 lazy_static! {
     static ref FAN_EVENT_METADATA_LEN: usize = mem::size_of::<FanotifyEvent>();
     static ref BEGIN_TIME: Instant = Instant::now();
@@ -72,6 +83,12 @@ const FAN_OPEN: u64 = 0x0000_0020;
 const FAN_OPEN_EXEC: u64 = 0x00001000;
 const AT_FDCWD: i32 = -100;
 
+// Depth of the channel that hands dequeued fanotify events off from the
+// reader task to the resolver workers. Bounded so a slow resolver applies
+// back-pressure instead of silently dropping events.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+const EVENT_WORKER_COUNT: usize = 4;
+
 #[allow(dead_code)]
 #[derive(Debug)]
 enum SetnsError {
@@ -94,14 +111,90 @@ fn get_target() -> String {
     env::var("_TARGET").map_or(DEFAULT_TARGET.to_string(), |str| str)
 }
 
+fn get_event_socket_path() -> Option<String> {
+    env::var("_EVENT_SOCKET").ok().filter(|path| !path.is_empty())
+}
+
 fn get_fd_path(fd: i32) -> io::Result<PathBuf> {
     let fd_path = format!("/proc/self/fd/{fd}");
     fs::read_link(fd_path)
 }
 
-fn set_ns(ns_path: String, flags: CloneFlags) -> Result<(), SetnsError> {
-    let file = fs::File::open(Path::new(ns_path.as_str())).map_err(SetnsError::IO)?;
-    setns(file.as_raw_fd(), flags).map_err(SetnsError::Nix)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NamespaceKind {
+    User,
+    Mount,
+    Net,
+    Pid,
+    Ipc,
+    Uts,
+}
+
+impl NamespaceKind {
+    fn proc_name(self) -> &'static str {
+        match self {
+            NamespaceKind::User => "user",
+            NamespaceKind::Mount => "mnt",
+            NamespaceKind::Net => "net",
+            NamespaceKind::Pid => "pid",
+            NamespaceKind::Ipc => "ipc",
+            NamespaceKind::Uts => "uts",
+        }
+    }
+
+    fn clone_flag(self) -> CloneFlags {
+        match self {
+            NamespaceKind::User => CloneFlags::CLONE_NEWUSER,
+            NamespaceKind::Mount => CloneFlags::CLONE_NEWNS,
+            NamespaceKind::Net => CloneFlags::CLONE_NEWNET,
+            NamespaceKind::Pid => CloneFlags::CLONE_NEWPID,
+            NamespaceKind::Ipc => CloneFlags::CLONE_NEWIPC,
+            NamespaceKind::Uts => CloneFlags::CLONE_NEWUTS,
+        }
+    }
+
+    fn parse(name: &str) -> Option<NamespaceKind> {
+        match name {
+            "user" => Some(NamespaceKind::User),
+            "mnt" | "mount" => Some(NamespaceKind::Mount),
+            "net" => Some(NamespaceKind::Net),
+            "pid" => Some(NamespaceKind::Pid),
+            "ipc" => Some(NamespaceKind::Ipc),
+            "uts" => Some(NamespaceKind::Uts),
+            _ => None,
+        }
+    }
+}
+
+const DEFAULT_JOIN_NS: &[NamespaceKind] = &[NamespaceKind::Pid, NamespaceKind::Mount];
+
+// `_JOIN_NS` is a comma-separated list (e.g. "user,mnt,net,pid,ipc,uts")
+// letting callers pull the optimizer into the full namespace set of a
+// rootless/user-namespaced container rather than just pid+mnt.
+fn get_join_namespaces() -> Vec<NamespaceKind> {
+    let kinds: Vec<NamespaceKind> = match env::var("_JOIN_NS") {
+        Ok(value) => value
+            .split(',')
+            .filter_map(|name| {
+                let name = name.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                let kind = NamespaceKind::parse(name);
+                if kind.is_none() {
+                    eprintln!("ignoring unknown namespace kind {name:?} in _JOIN_NS");
+                }
+                kind
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if kinds.is_empty() {
+        DEFAULT_JOIN_NS.to_vec()
+    } else {
+        kinds
+    }
 }
 
 fn init_fanotify() -> Result<i32, io::Error> {
@@ -137,11 +230,15 @@ fn read_fanotify(fanotify_fd: i32) -> Vec<FanotifyEvent> {
     unsafe {
         let buffer = libc::malloc(*FAN_EVENT_METADATA_LEN * 1024);
         let sizeof = libc::read(fanotify_fd, buffer, *FAN_EVENT_METADATA_LEN * 1024);
-        let src = slice::from_raw_parts(
-            buffer as *mut FanotifyEvent,
-            sizeof as usize / *FAN_EVENT_METADATA_LEN,
-        );
-        vec.extend_from_slice(src);
+        // `fanotify_fd` is FAN_NONBLOCK, so a drained queue surfaces as a
+        // negative return (EAGAIN) rather than a short read.
+        if sizeof > 0 {
+            let src = slice::from_raw_parts(
+                buffer as *mut FanotifyEvent,
+                sizeof as usize / *FAN_EVENT_METADATA_LEN,
+            );
+            vec.extend_from_slice(src);
+        }
         libc::free(buffer);
     }
     vec
@@ -153,76 +250,552 @@ fn close_fd(fd: i32) {
     }
 }
 
+// Where emitted `EventInfo`s go. `Socket` carries an already-connected
+// `SOCK_SEQPACKET` fd: every `send()` on it is delivered to the reader as
+// exactly one datagram, so unlike stdout there's no partial-line framing
+// for the collector to re-split.
+#[derive(Debug, Clone, Copy)]
+enum EventTransport {
+    Stdout,
+    Socket(i32),
+}
+
+fn connect_event_socket(path: &str) -> io::Result<i32> {
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path_bytes = path.as_bytes();
+    if path_bytes.len() >= addr.sun_path.len() {
+        close_fd(fd);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("event socket path too long: {path}"),
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path_bytes.iter()) {
+        *dst = *src as libc::c_char;
+    }
+    let addr_len = mem::size_of::<libc::sa_family_t>() + path_bytes.len() + 1;
+
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            addr_len as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        let e = io::Error::last_os_error();
+        close_fd(fd);
+        return Err(e);
+    }
+
+    Ok(fd)
+}
+
+fn send_on_event_socket(fd: i32, payload: &[u8]) -> io::Result<()> {
+    // MSG_NOSIGNAL keeps a collector restart from raising SIGPIPE (default
+    // disposition: terminate); a closed socket is surfaced as an io::Error
+    // below instead.
+    let ret = unsafe {
+        libc::send(
+            fd,
+            payload.as_ptr() as *const libc::c_void,
+            payload.len(),
+            libc::MSG_NOSIGNAL,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Stdout is the default transport; selecting `_EVENT_SOCKET=/path` connects
+// to a pre-existing `SOCK_SEQPACKET` socket instead.
+fn get_event_transport() -> EventTransport {
+    match get_event_socket_path() {
+        Some(path) => match connect_event_socket(&path) {
+            Ok(fd) => EventTransport::Socket(fd),
+            Err(e) => {
+                eprintln!("failed to connect to event socket {path}: {e}, falling back to stdout");
+                EventTransport::Stdout
+            }
+        },
+        None => EventTransport::Stdout,
+    }
+}
+
+fn send_stream_complete(transport: EventTransport) {
+    if let EventTransport::Socket(fd) = transport {
+        let payload = match serde_json::to_vec(&StreamComplete { complete: true }) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("failed to encode stream-complete frame: {e:?}");
+                return;
+            }
+        };
+        if let Err(e) = send_on_event_socket(fd, &payload) {
+            eprintln!("failed to send stream-complete frame: {e}");
+        }
+        close_fd(fd);
+    }
+}
+
+// Lets a bare fanotify fd be registered with tokio's reactor, which requires
+// `AsRawFd` rather than a plain `i32`.
+struct FanotifyReactorFd(i32);
+
+impl AsRawFd for FanotifyReactorFd {
+    fn as_raw_fd(&self) -> i32 {
+        self.0
+    }
+}
+
+// Opens a pidfd for `pid`, which stays valid (and POLLIN-readable on exit)
+// even if `pid` itself is later recycled by the kernel. Returns `ENOSYS` on
+// kernels older than 5.3 that don't implement the syscall.
+fn pidfd_open(pid: i32) -> io::Result<i32> {
+    match unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(fd as i32),
+    }
+}
+
+// Signals the process referred to by `pidfd` rather than a raw pid, so the
+// signal can never land on an unrelated process that reused the same pid.
+fn pidfd_send_signal(pidfd: i32, signal: i32) -> io::Result<()> {
+    match unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd,
+            signal,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn statx_size(path: &Path) -> io::Result<u64> {
+    let c_path = ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut statx_buf: libc::statx = unsafe { mem::zeroed() };
+    let ret = unsafe {
+        libc::statx(
+            AT_FDCWD,
+            c_path.as_ptr(),
+            libc::AT_STATX_SYNC_AS_STAT,
+            libc::STATX_SIZE,
+            &mut statx_buf,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(statx_buf.stx_size)
+}
+
 fn generate_event_info(path: &Path) -> Result<EventInfo, io::Error> {
-    fs::metadata(path).map(|metadata| EventInfo {
+    statx_size(path).map(|size| EventInfo {
         path: path.to_string_lossy().to_string(),
-        size: metadata.len(),
+        size,
         elapsed: BEGIN_TIME.elapsed().as_micros(),
     })
 }
 
-fn send_event(event: &EventInfo) -> Result<(), SendError> {
-    let mut writer = io::stdout();
+fn send_event(event: &EventInfo, transport: EventTransport) -> Result<(), SendError> {
     let event_string = serde_json::to_string(event).map_err(SendError::Serde)?;
-    writer
-        .write_all(format!("{event_string}\n").as_bytes())
-        .map_err(SendError::IO)?;
-    writer.flush().map_err(SendError::IO)
+    match transport {
+        EventTransport::Stdout => {
+            let mut writer = io::stdout();
+            writer
+                .write_all(format!("{event_string}\n").as_bytes())
+                .map_err(SendError::IO)?;
+            writer.flush().map_err(SendError::IO)
+        }
+        EventTransport::Socket(fd) => {
+            send_on_event_socket(fd, event_string.as_bytes()).map_err(SendError::IO)
+        }
+    }
 }
 
-fn handle_event(event: &FanotifyEvent, event_duplicate: &mut Vec<String>) -> Result<(), SendError> {
+// `event_duplicate` is shared across the worker pool, so dedup is guarded by
+// a mutex rather than the single `Vec` a single-threaded loop could own
+// outright.
+fn handle_event(
+    event: &FanotifyEvent,
+    event_duplicate: &Mutex<Vec<String>>,
+    transport: EventTransport,
+) -> Result<(), SendError> {
     let path = get_fd_path(event.fd).map_err(SendError::IO)?;
     let info = generate_event_info(&path).map_err(SendError::IO)?;
-    if !event_duplicate.contains(&info.path) {
-        send_event(&info)?;
-        event_duplicate.push(info.path);
+    let mut seen = event_duplicate.lock().unwrap();
+    if !seen.contains(&info.path) {
+        send_event(&info, transport)?;
+        seen.push(info.path);
     }
     Ok(())
 }
 
-fn handle_fanotify_event(fd: i32) {
-    let mut event_duplicate = Vec::new();
-    let (reader, writer) = match UnixStream::pair() {
-        Ok((reader, writer)) => (reader, writer),
+// The set of signals that should terminate the fanotify loop. Blocking them
+// up front and reading them back through a signalfd (instead of a
+// self-pipe handler) avoids doing any work in an async-signal-unsafe
+// context and makes it trivial to react differently per signal.
+fn termination_mask() -> SigSet {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    mask.add(Signal::SIGHUP);
+    mask
+}
+
+// Owns the fanotify fd and does nothing but drain it into `tx` as fast as
+// the kernel delivers events, so the fanotify queue only waits on the
+// workers' blocking path/statx resolution once the channel already holds
+// `EVENT_CHANNEL_CAPACITY` events.
+async fn fanotify_producer(fd: i32, tx: mpsc::Sender<FanotifyEvent>) {
+    let async_fd = match AsyncFd::new(FanotifyReactorFd(fd)) {
+        Ok(async_fd) => async_fd,
         Err(e) => {
-            eprintln!("failed to create a pair of sockets: {e:?}");
+            eprintln!("failed to register fanotify fd with reactor {e:?}");
             return;
         }
     };
-    if let Err(e) = pipe::register(SIGTERM, writer) {
-        eprintln!("failed to set SIGTERM signal handler {e:?}");
+
+    loop {
+        let mut guard = match async_fd.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                eprintln!("failed to poll fanotify fd {e:?}");
+                return;
+            }
+        };
+
+        let events = guard.try_io(|_| {
+            let events = read_fanotify(fd);
+            if events.is_empty() {
+                Err(io::Error::from(io::ErrorKind::WouldBlock))
+            } else {
+                Ok(events)
+            }
+        });
+
+        let events = match events {
+            Ok(Ok(events)) => events,
+            Ok(Err(e)) => {
+                eprintln!("failed to read fanotify events {e:?}");
+                return;
+            }
+            Err(_would_block) => continue,
+        };
+
+        for event in events {
+            // `event.fd` is a plain i32 and is just as usable from whichever
+            // worker task ends up receiving it, so it's sent through the
+            // channel unchanged rather than duplicated.
+            if tx.send(event).await.is_err() {
+                close_fd(event.fd);
+                return;
+            }
+        }
+    }
+}
+
+// Pulls events off the shared channel and does the blocking work (resolve
+// path, statx, dedup, emit) that used to sit in the hot dequeue loop.
+async fn fanotify_worker(
+    rx: Arc<AsyncMutex<mpsc::Receiver<FanotifyEvent>>>,
+    event_duplicate: Arc<Mutex<Vec<String>>>,
+    transport: EventTransport,
+) {
+    loop {
+        let event = {
+            let mut rx = rx.lock().await;
+            rx.recv().await
+        };
+        let event = match event {
+            Some(event) => event,
+            None => break,
+        };
+
+        let dedup = event_duplicate.clone();
+        // Resolving the path and statx-ing it are blocking calls, so they
+        // run on tokio's blocking pool rather than the small fixed set of
+        // worker threads that also has to keep polling the producer.
+        let result = tokio::task::spawn_blocking(move || {
+            let result = handle_event(&event, &dedup, transport);
+            close_fd(event.fd);
+            result
+        })
+        .await;
+
+        match result {
+            Ok(Err(e)) => eprintln!("failed to handle event {event:?} {e:?}"),
+            Ok(Ok(())) => {}
+            Err(e) => eprintln!("fanotify worker task panicked: {e:?}"),
+        }
+    }
+}
+
+fn drain_signalfd(signal_fd: &SignalFd) -> io::Result<bool> {
+    let mut terminated = false;
+    loop {
+        match signal_fd.read_signal() {
+            Ok(Some(siginfo)) => {
+                println!("received signal {}", siginfo.ssi_signo);
+                terminated = true;
+            }
+            Ok(None) => break,
+            Err(nix::Error::EAGAIN) => break,
+            Err(e) => return Err(io::Error::from(e)),
+        }
+    }
+    if terminated {
+        Ok(true)
+    } else {
+        Err(io::Error::from(io::ErrorKind::WouldBlock))
+    }
+}
+
+// Returns true once a termination signal has been read, false if the
+// signalfd could not be registered, polled or read.
+async fn wait_for_termination(signal_fd: SignalFd) -> bool {
+    let async_fd = match AsyncFd::new(signal_fd) {
+        Ok(async_fd) => async_fd,
+        Err(e) => {
+            eprintln!("failed to register signalfd with reactor {e:?}");
+            return false;
+        }
+    };
+
+    loop {
+        let mut guard = match async_fd.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                eprintln!("failed to poll signalfd {e:?}");
+                return false;
+            }
+        };
+
+        match guard.try_io(|inner| drain_signalfd(inner.get_ref())) {
+            Ok(Ok(true)) => return true,
+            Ok(Ok(false)) => continue,
+            Ok(Err(e)) => {
+                eprintln!("failed to read signalfd {e:?}");
+                return false;
+            }
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+async fn run_fanotify_loop(fd: i32, signal_fd: SignalFd) {
+    let transport = get_event_transport();
+    let (tx, rx) = mpsc::channel::<FanotifyEvent>(EVENT_CHANNEL_CAPACITY);
+    let event_duplicate = Arc::new(Mutex::new(Vec::new()));
+    let rx = Arc::new(AsyncMutex::new(rx));
+
+    let workers: Vec<_> = (0..EVENT_WORKER_COUNT)
+        .map(|_| tokio::spawn(fanotify_worker(rx.clone(), event_duplicate.clone(), transport)))
+        .collect();
+    let producer = tokio::spawn(fanotify_producer(fd, tx));
+
+    let terminated = wait_for_termination(signal_fd).await;
+    if terminated {
+        println!("received termination signal, shutting down");
+    }
+
+    producer.abort();
+    for worker in workers {
+        worker.abort();
+    }
+
+    if terminated {
+        send_stream_complete(transport);
+    } else if let EventTransport::Socket(fd) = transport {
+        close_fd(fd);
+    }
+}
+
+fn handle_fanotify_event(fd: i32) {
+    // Block the termination signals on this thread before the runtime is
+    // built: tokio's multi-thread worker pthreads inherit whatever mask is
+    // in effect on the thread that spawns them, so blocking it any later
+    // (e.g. from inside a task running on one of those workers) would leave
+    // the workers unblocked and able to take the signal via its default
+    // (terminating) disposition.
+    let mask = termination_mask();
+    if let Err(e) = mask.thread_block() {
+        eprintln!("failed to block termination signals {e:?}");
         return;
     }
+    let signal_fd = match SignalFd::with_flags(&mask, SfdFlags::SFD_CLOEXEC | SfdFlags::SFD_NONBLOCK) {
+        Ok(signal_fd) => signal_fd,
+        Err(e) => {
+            eprintln!("failed to create signalfd {e:?}");
+            return;
+        }
+    };
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .worker_threads(EVENT_WORKER_COUNT)
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("failed to build tokio runtime {e:?}");
+            return;
+        }
+    };
+    runtime.block_on(run_fanotify_loop(fd, signal_fd));
+}
+
+fn start_fanotify() -> Result<(), io::Error> {
+    let fd = init_fanotify()?;
+    mark_fanotify(fd, get_target().as_str())?;
+    handle_fanotify_event(fd);
+    Ok(())
+}
+
+// Opens every target namespace fd up front so a namespace that cannot be
+// opened is reported before any namespace is entered, then enters them with
+// the user namespace first (so the later joins inherit the right
+// credentials) and the rest after. A `setns` that fails after earlier ones
+// succeeded leaves the process in those earlier namespaces.
+fn join_namespace(pid: String) -> Result<(), SetnsError> {
+    let mut targets: Vec<(CloneFlags, fs::File)> = get_join_namespaces()
+        .into_iter()
+        .map(|kind| {
+            let path = format!("/proc/{pid}/ns/{}", kind.proc_name());
+            fs::File::open(Path::new(path.as_str()))
+                .map(|file| (kind.clone_flag(), file))
+                .map_err(SetnsError::IO)
+        })
+        .collect::<Result<_, _>>()?;
+
+    targets.sort_by_key(|(flags, _)| if *flags == CloneFlags::CLONE_NEWUSER {
+        0
+    } else {
+        1
+    });
+
+    for (flags, file) in &targets {
+        setns(file.as_raw_fd(), *flags).map_err(SetnsError::Nix)?;
+    }
+
+    Ok(())
+}
+
+// Maps a `WaitStatus` to a descriptive error for anything but a clean exit,
+// so `main()` can tell "fanotify init failed" apart from "child was killed
+// by SIGTERM during shutdown" instead of always exiting 0.
+fn check(status: WaitStatus) -> io::Result<()> {
+    match status {
+        WaitStatus::Exited(_, 0) => Ok(()),
+        WaitStatus::Exited(pid, code) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("child process {pid} exited with code {code}"),
+        )),
+        WaitStatus::Signaled(pid, signal, _) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("child process {pid} was killed by signal {signal}"),
+        )),
+        WaitStatus::Stopped(pid, signal) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("child process {pid} was stopped by signal {signal}"),
+        )),
+        other => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unexpected wait status {other:?}"),
+        )),
+    }
+}
+
+// Mirrors the shell convention of reporting a terminating signal as 128+N,
+// so scripts polling this tool's exit status can distinguish the two.
+fn exit_code(status: WaitStatus) -> i32 {
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        _ => 1,
+    }
+}
+
+fn reap_child(child: Pid) -> i32 {
+    match waitpid(child, None) {
+        Ok(status) => {
+            if let Err(e) = check(status) {
+                eprintln!("{e}");
+            }
+            exit_code(status)
+        }
+        Err(e) => {
+            eprintln!("failed to wait for child process: {e}");
+            1
+        }
+    }
+}
+
+// Supervises `child` via a pidfd so a received SIGTERM can be forwarded to
+// exactly that process (never an impostor that reused its pid) and the exit
+// can be observed through the same poll() loop that watches for SIGTERM.
+// SIGTERM is read back through a signalfd rather than a self-pipe, for the
+// same async-signal-safety reasons the fanotify loop uses one.
+fn supervise_with_pidfd(child: Pid, pidfd: i32) -> i32 {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    if let Err(e) = mask.thread_block() {
+        eprintln!("failed to block SIGTERM {e:?}");
+        close_fd(pidfd);
+        return reap_child(child);
+    }
+    let signal_fd = match SignalFd::with_flags(&mask, SfdFlags::SFD_CLOEXEC) {
+        Ok(signal_fd) => signal_fd,
+        Err(e) => {
+            eprintln!("failed to create signalfd {e:?}");
+            close_fd(pidfd);
+            return reap_child(child);
+        }
+    };
+
     let mut fds = [
-        PollFd::new(fd.as_raw_fd(), PollFlags::POLLIN),
-        PollFd::new(reader.as_raw_fd(), PollFlags::POLLIN),
+        PollFd::new(pidfd, PollFlags::POLLIN),
+        PollFd::new(signal_fd.as_raw_fd(), PollFlags::POLLIN),
     ];
 
-    loop {
+    let code = loop {
         match poll(&mut fds, -1) {
             Ok(polled_num) => {
                 if polled_num <= 0 {
                     eprintln!("polled_num <= 0!");
-                    break;
+                    break 1;
                 }
 
-                if let Some(flag) = fds[0].revents() {
+                if let Some(flag) = fds[1].revents() {
                     if flag.contains(PollFlags::POLLIN) {
-                        let events = read_fanotify(fd);
-                        for event in events {
-                            if let Err(e) = handle_event(&event, &mut event_duplicate) {
-                                eprintln!("failed to handle event {event:?} {e:?}")
-                            };
-                            // No matter the target path is valid or not, we should close the fd
-                            close_fd(event.fd);
+                        match signal_fd.read_signal() {
+                            Ok(Some(_)) => {
+                                eprintln!("received SIGTERM signal, forwarding to child {child}");
+                                if let Err(e) = pidfd_send_signal(pidfd, libc::SIGTERM) {
+                                    eprintln!("failed to forward SIGTERM to child {child}: {e}");
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => eprintln!("failed to read signalfd {e:?}"),
                         }
                     }
                 }
 
-                if let Some(flag) = fds[1].revents() {
+                if let Some(flag) = fds[0].revents() {
                     if flag.contains(PollFlags::POLLIN) {
-                        println!("received SIGTERM signal");
-                        break;
+                        break reap_child(child);
                     }
                 }
             }
@@ -231,23 +804,27 @@ fn handle_fanotify_event(fd: i32) {
                     continue;
                 }
                 eprintln!("Poll error {:?}", e);
-                break;
+                break 1;
             }
         }
-    }
-}
+    };
 
-fn start_fanotify() -> Result<(), io::Error> {
-    let fd = init_fanotify()?;
-    mark_fanotify(fd, get_target().as_str())?;
-    handle_fanotify_event(fd);
-    Ok(())
+    close_fd(pidfd);
+    code
 }
 
-fn join_namespace(pid: String) -> Result<(), SetnsError> {
-    set_ns(format!("/proc/{pid}/ns/pid"), CloneFlags::CLONE_NEWPID)?;
-    set_ns(format!("/proc/{pid}/ns/mnt"), CloneFlags::CLONE_NEWNS)?;
-    Ok(())
+fn supervise_child(child: Pid) -> i32 {
+    match pidfd_open(child.as_raw()) {
+        Ok(pidfd) => supervise_with_pidfd(child, pidfd),
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+            eprintln!("pidfd_open unsupported on this kernel, falling back to waitpid");
+            reap_child(child)
+        }
+        Err(e) => {
+            eprintln!("failed to open pidfd for child {child}: {e}, falling back to waitpid");
+            reap_child(child)
+        }
+    }
 }
 
 fn main() {
@@ -262,6 +839,7 @@ fn main() {
         Ok(ForkResult::Child) => {
             if let Err(e) = start_fanotify() {
                 eprintln!("failed to start fanotify server {e:?}");
+                std::process::exit(1);
             }
         }
         Ok(ForkResult::Parent { child }) => {
@@ -271,18 +849,7 @@ fn main() {
                 eprintln!("failed to get pgid of {child} {e:?}");
             }
 
-            match waitpid(child, None) {
-                Ok(WaitStatus::Signaled(pid, signal, _)) => {
-                    eprintln!("child process {pid} was killed by signal {signal}");
-                }
-                Ok(WaitStatus::Stopped(pid, signal)) => {
-                    eprintln!("child process {pid} was stopped by signal {signal}");
-                }
-                Err(e) => {
-                    eprintln!("failed to wait for child process: {e}");
-                }
-                _ => {}
-            }
+            std::process::exit(supervise_child(child));
         }
         Err(e) => {
             eprintln!("fork failed: unable to create child process: {e:?}");